@@ -1,11 +1,16 @@
 use anyhow::Error;
+use std::borrow::Cow;
 use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use encoding_rs::Encoding;
+
 use helix_core::{
-    syntax::LOADER, ChangeSet, Diagnostic, History, Rope, Selection, State, Syntax, Transaction,
+    syntax::LOADER, Assoc, ChangeSet, Diagnostic, History, Range, Rope, Selection, State, Syntax,
+    Tendril, Transaction,
 };
+use smallvec::SmallVec;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Mode {
@@ -14,6 +19,448 @@ pub enum Mode {
     Goto,
 }
 
+/// Line ending style used by a document on disk.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        LineEnding::Lf
+    }
+}
+
+/// Encoding used when a document's encoding can't be sniffed from a BOM.
+pub const DEFAULT_ENCODING: &Encoding = encoding_rs::UTF_8;
+
+/// How many lines to scan from the start of a file to guess its dominant line ending.
+const LINE_ENDING_SCAN_LIMIT: usize = 1000;
+
+/// Decode `data` using a BOM if one is present, otherwise `default_encoding`. Also reports
+/// whether a BOM was actually found, so `save` can re-emit one.
+fn decode(data: &[u8], default_encoding: &'static Encoding) -> (&'static Encoding, bool, String) {
+    let (encoding, bom_len) = Encoding::for_bom(data).unwrap_or((default_encoding, 0));
+    let (text, _, _) = encoding.decode(&data[bom_len..]);
+    (encoding, bom_len > 0, text.into_owned())
+}
+
+/// The BOM bytes `encoding` is conventionally prefixed with, if any. `encoding_rs` only sniffs
+/// BOMs on decode; re-emitting one on save is left to the caller.
+fn bom_bytes(encoding: &'static Encoding) -> &'static [u8] {
+    if encoding == encoding_rs::UTF_8 {
+        &[0xEF, 0xBB, 0xBF]
+    } else if encoding == encoding_rs::UTF_16LE {
+        &[0xFF, 0xFE]
+    } else if encoding == encoding_rs::UTF_16BE {
+        &[0xFE, 0xFF]
+    } else {
+        &[]
+    }
+}
+
+/// Encode `text` into `encoding`. `encoding_rs` has no UTF-16 encoder: both `Encoding::encode`
+/// and `Encoding::new_encoder` use `output_encoding()`, which is UTF-8 for `UTF_16LE`/`UTF_16BE`
+/// and would silently rewrite a UTF-16 file as UTF-8 on every save. So UTF-16 is encoded here
+/// by hand, and every other encoding goes through its stateful encoder.
+fn encode_str(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+    if encoding == encoding_rs::UTF_16LE {
+        return text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    }
+    if encoding == encoding_rs::UTF_16BE {
+        return text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    }
+    let mut encoder = encoding.new_encoder();
+    let mut out = Vec::with_capacity(text.len());
+    let mut input = text;
+    loop {
+        let mut buf = [0u8; 8192];
+        let (result, read, written, _) = encoder.encode_from_utf8(input, &mut buf, true);
+        out.extend_from_slice(&buf[..written]);
+        input = &input[read..];
+        if let encoding_rs::CoderResult::InputEmpty = result {
+            break;
+        }
+    }
+    out
+}
+
+/// Guess the dominant line ending of `text` by scanning its first few lines and tallying
+/// which ending is most common, rather than trusting the very first one seen.
+fn detect_line_ending(text: &str) -> LineEnding {
+    let (mut lf, mut crlf, mut cr) = (0usize, 0usize, 0usize);
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    let mut lines_scanned = 0;
+    while i < bytes.len() && lines_scanned < LINE_ENDING_SCAN_LIMIT {
+        match bytes[i] {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                crlf += 1;
+                lines_scanned += 1;
+                i += 2;
+                continue;
+            }
+            b'\r' => {
+                cr += 1;
+                lines_scanned += 1;
+            }
+            b'\n' => {
+                lf += 1;
+                lines_scanned += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if crlf >= lf && crlf >= cr && crlf > 0 {
+        LineEnding::CrLf
+    } else if cr > lf {
+        LineEnding::Cr
+    } else {
+        LineEnding::default()
+    }
+}
+
+/// Normalize `text` to `\n` line endings, returning it unchanged when already LF.
+fn normalize_line_endings(text: String, line_ending: LineEnding) -> String {
+    match line_ending {
+        LineEnding::Lf => text,
+        LineEnding::CrLf => text.replace("\r\n", "\n"),
+        LineEnding::Cr => text.replace("\r\n", "\n").replace('\r', "\n"),
+    }
+}
+
+/// A cheap fingerprint of a file's state on disk (mtime + size), used to notice when a file
+/// was changed by another process between when we last touched it and when we're about to
+/// save over it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DiskStamp {
+    mtime: std::time::SystemTime,
+    len: u64,
+}
+
+impl DiskStamp {
+    fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            mtime: metadata
+                .modified()
+                .unwrap_or(std::time::SystemTime::UNIX_EPOCH),
+            len: metadata.len(),
+        }
+    }
+}
+
+/// Errors that can occur while saving a `Document`.
+#[derive(Debug, thiserror::Error)]
+pub enum SaveError {
+    /// The file changed on disk since we last loaded or saved it. Saving now would silently
+    /// clobber those external changes, so the caller should prompt the user instead.
+    #[error("file was modified on disk since it was last loaded or saved")]
+    ModifiedOnDisk,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Path to the sibling temp file `save` writes to before renaming it over `path`, so a crash
+/// mid-write can never leave `path` truncated.
+fn tmp_path_for(path: &Path) -> PathBuf {
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    path.with_file_name(tmp_name)
+}
+
+/// Format version stamped into persisted history files, so a future incompatible format can
+/// refuse to load an old one instead of misinterpreting it.
+const HISTORY_FORMAT_VERSION: u32 = 1;
+
+/// Default directory persisted undo histories are written under. Override per-document with
+/// `Document::set_history_dir`.
+fn default_history_dir() -> PathBuf {
+    std::env::var_os("HELIX_HISTORY_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("helix").join("history"))
+}
+
+/// Cheap content hash used both to key a persisted history file to the file it was recorded
+/// against, and to notice that the on-disk file has since been edited externally.
+///
+/// Must hash identically whether fed as one slice or as a sequence of chunks (`save` hashes
+/// the encoded output chunk by chunk as it's written), so this writes the raw bytes directly
+/// rather than going through `Hash for [u8]`, which prepends a length and would make the two
+/// call sites disagree.
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    std::hash::Hasher::write(&mut hasher, data);
+    std::hash::Hasher::finish(&hasher)
+}
+
+/// Path of the persisted history file for `path`, keyed by its canonicalized form plus the
+/// content hash of the file it was recorded against.
+fn history_path(history_dir: &Path, path: &Path, hash: u64) -> PathBuf {
+    let sanitized: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    history_dir.join(format!(
+        "{}-{:016x}.histv{}",
+        sanitized, hash, HISTORY_FORMAT_VERSION
+    ))
+}
+
+/// Append a length-prefixed string to `out`.
+fn push_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Read a length-prefixed string previously written by `push_string`, advancing `data` past it.
+fn pop_string<'a>(data: &mut &'a [u8]) -> Option<&'a str> {
+    if data.len() < 8 {
+        return None;
+    }
+    let len = u64::from_le_bytes(data[0..8].try_into().unwrap()) as usize;
+    *data = &data[8..];
+    if data.len() < len {
+        return None;
+    }
+    let s = std::str::from_utf8(&data[..len]).ok()?;
+    *data = &data[len..];
+    Some(s)
+}
+
+/// Append a `Selection` to `out`: range count, primary index, then each range's `(anchor,
+/// head)` pair.
+fn push_selection(out: &mut Vec<u8>, selection: &Selection) {
+    let ranges = selection.ranges();
+    out.extend_from_slice(&(ranges.len() as u64).to_le_bytes());
+    out.extend_from_slice(&(selection.primary_index() as u64).to_le_bytes());
+    for range in ranges {
+        out.extend_from_slice(&(range.anchor as u64).to_le_bytes());
+        out.extend_from_slice(&(range.head as u64).to_le_bytes());
+    }
+}
+
+/// Inverse of `push_selection`, advancing `data` past it.
+fn pop_selection(data: &mut &[u8]) -> Option<Selection> {
+    if data.len() < 16 {
+        return None;
+    }
+    let len = u64::from_le_bytes(data[0..8].try_into().ok()?) as usize;
+    let primary_index = u64::from_le_bytes(data[8..16].try_into().ok()?) as usize;
+    *data = &data[16..];
+
+    let mut ranges = Vec::with_capacity(len);
+    for _ in 0..len {
+        if data.len() < 16 {
+            return None;
+        }
+        let anchor = u64::from_le_bytes(data[0..8].try_into().ok()?) as usize;
+        let head = u64::from_le_bytes(data[8..16].try_into().ok()?) as usize;
+        *data = &data[16..];
+        ranges.push(Range::new(anchor, head));
+    }
+
+    Some(Selection::new(SmallVec::from_vec(ranges), primary_index))
+}
+
+/// One committed edit in a persisted `revision_log`: a plain `(start, end, replacement)` span
+/// - the same shape `diff_as_change` produces and `Transaction::change` consumes - together
+/// with the `Selection` that was current right after the edit (the same one
+/// `append_changes_to_history` attaches via `.with_selection`), so replaying the log restores
+/// the cursor the edit left behind, not just the text.
+type RevisionLogEntry = (usize, usize, Option<String>, Selection);
+
+/// Apply one `RevisionLogEntry` to `state` and commit it into `history`, the same way
+/// `append_changes_to_history` does live. Returns `false` (without mutating `history`) if the
+/// entry doesn't apply, so the caller can bail out of a corrupt or stale log.
+fn replay_revision_log_entry(
+    state: &mut State,
+    history: &mut History,
+    entry: &RevisionLogEntry,
+) -> bool {
+    let (start, end, replacement, selection) = entry;
+    let old_state = state.clone();
+    let transaction = Transaction::change(
+        state,
+        std::iter::once((*start, *end, replacement.clone().map(Tendril::from))),
+    )
+    .with_selection(selection.clone());
+    if !transaction.apply(state) {
+        return false;
+    }
+    history.commit_revision(&transaction, &old_state);
+    true
+}
+
+/// Encode the undo history we persist across restarts.
+///
+/// `helix_core::Transaction`/`ChangeSet` have no serialization support, and adding it isn't ours
+/// to do from this crate, so rather than persist `History` itself we persist the minimal data we
+/// need to rebuild one: the text as it was when `base` was captured (load time), plus each
+/// committed edit since then as a `RevisionLogEntry`. Loading replays these forward over `base`
+/// with `Transaction::change` + `History::commit_revision` to reconstruct an equivalent
+/// `History`. `cursor` records how many of `log` had been committed (as opposed to undone) as of
+/// the save, so `is_modified` can be restored too.
+fn encode_revision_log(base: &Rope, log: &[RevisionLogEntry], cursor: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_string(&mut data, &base.to_string());
+    data.extend_from_slice(&(cursor as u64).to_le_bytes());
+    data.extend_from_slice(&(log.len() as u64).to_le_bytes());
+    for (start, end, replacement, selection) in log {
+        data.extend_from_slice(&(*start as u64).to_le_bytes());
+        data.extend_from_slice(&(*end as u64).to_le_bytes());
+        match replacement {
+            Some(text) => {
+                data.push(1);
+                push_string(&mut data, text);
+            }
+            None => data.push(0),
+        }
+        push_selection(&mut data, selection);
+    }
+    data
+}
+
+/// Inverse of `encode_revision_log`. Returns `None` on any malformed input rather than panicking
+/// on a corrupt or truncated history file.
+fn decode_revision_log(mut data: &[u8]) -> Option<(Rope, Vec<RevisionLogEntry>, usize)> {
+    let base = Rope::from_str(pop_string(&mut data)?);
+
+    if data.len() < 16 {
+        return None;
+    }
+    let cursor = u64::from_le_bytes(data[0..8].try_into().ok()?) as usize;
+    let len = u64::from_le_bytes(data[8..16].try_into().ok()?) as usize;
+    data = &data[16..];
+
+    let mut log = Vec::with_capacity(len);
+    for _ in 0..len {
+        if data.len() < 17 {
+            return None;
+        }
+        let start = u64::from_le_bytes(data[0..8].try_into().ok()?) as usize;
+        let end = u64::from_le_bytes(data[8..16].try_into().ok()?) as usize;
+        let has_replacement = data[16];
+        data = &data[17..];
+        let replacement = if has_replacement == 1 {
+            Some(pop_string(&mut data)?.to_string())
+        } else {
+            None
+        };
+        let selection = pop_selection(&mut data)?;
+        log.push((start, end, replacement, selection));
+    }
+
+    Some((base, log, cursor))
+}
+
+/// Prefix a serialized `History` payload with a format version and the content hash of the
+/// file it was recorded against.
+fn encode_history_file(base_hash: u64, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(12 + payload.len());
+    data.extend_from_slice(&HISTORY_FORMAT_VERSION.to_le_bytes());
+    data.extend_from_slice(&base_hash.to_le_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Validate a persisted history file's header against the file's current content hash,
+/// returning the inner payload on a match. Returns `None` (discarding the history rather than
+/// risking corrupt positions) on a version mismatch or if the file was edited externally since
+/// the history was recorded.
+fn decode_history_file(data: &[u8], expected_hash: u64) -> Option<&[u8]> {
+    if data.len() < 12 {
+        return None;
+    }
+    let version = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    if version != HISTORY_FORMAT_VERSION {
+        return None;
+    }
+    let base_hash = u64::from_le_bytes(data[4..12].try_into().unwrap());
+    if base_hash != expected_hash {
+        return None;
+    }
+    Some(&data[12..])
+}
+
+/// Remap a half-open `[start, end)` span - e.g. a `Diagnostic` range - through `changes`.
+///
+/// The two ends need opposite bias, not the same one: `start` is mapped with `Assoc::After` so
+/// text inserted exactly at it is pushed out of the span rather than prepended into it, while
+/// `end` is mapped with `Assoc::Before` so text inserted exactly at it stays excluded too,
+/// rather than silently growing the span. This mirrors how `helix_core::Range::map` gives a
+/// selection's anchor and head opposite bias for the same reason.
+fn remap_range(range: std::ops::Range<usize>, changes: &ChangeSet) -> std::ops::Range<usize> {
+    let start = changes.map_pos(range.start, Assoc::After);
+    let end = changes.map_pos(range.end, Assoc::Before);
+    start..end
+}
+
+/// Length, in chars, of the longest common prefix of `a` and `b`.
+fn common_prefix_len(a: &Rope, b: &Rope) -> usize {
+    let max = a.len_chars().min(b.len_chars());
+    let mut i = 0;
+    while i < max && a.char(i) == b.char(i) {
+        i += 1;
+    }
+    i
+}
+
+/// Length, in chars, of the longest common suffix of `a` and `b`, not overlapping the first
+/// `prefix_len` chars already claimed by `common_prefix_len`.
+fn common_suffix_len(a: &Rope, b: &Rope, prefix_len: usize) -> usize {
+    let max = (a.len_chars() - prefix_len).min(b.len_chars() - prefix_len);
+    let mut i = 0;
+    while i < max && a.char(a.len_chars() - 1 - i) == b.char(b.len_chars() - 1 - i) {
+        i += 1;
+    }
+    i
+}
+
+/// Describe the difference between `old` and `new` as a single minimal change span, rather
+/// than a wholesale replace, by trimming the common prefix and suffix. Returns `None` if the
+/// two are identical.
+///
+/// Known limitation: this only ever produces one hunk. Two independent edits (e.g. one near
+/// the top of the file and one near the bottom) share no common prefix/suffix trim with each
+/// other, so they collapse into a single replacement spanning everything in between, even
+/// though most of that span is unchanged - dragging selections that sit in the untouched middle
+/// through the "edit" and handing the incremental `Syntax` update a larger span than necessary.
+/// A real multi-hunk diff (e.g. Myers) would avoid this, but is more than `reload` needs today.
+fn diff_as_change(old: &Rope, new: &Rope) -> Option<(usize, usize, Option<Tendril>)> {
+    let prefix = common_prefix_len(old, new);
+    let suffix = common_suffix_len(old, new, prefix);
+    let old_end = old.len_chars() - suffix;
+    let new_end = new.len_chars() - suffix;
+
+    if prefix == old_end && prefix == new_end {
+        return None;
+    }
+
+    let inserted = new.slice(prefix..new_end).to_string();
+    let inserted = if inserted.is_empty() {
+        None
+    } else {
+        Some(inserted.into())
+    };
+    Some((prefix, old_end, inserted))
+}
+
 pub struct Document {
     pub state: State, // rope + selection
     /// File path on disk.
@@ -34,9 +481,42 @@ pub struct Document {
     old_state: Option<State>,
     /// Undo tree.
     history: History,
+    /// Text as it stood when `revision_log` started tracking edits (i.e. at load time, or at
+    /// the last point we had no persisted history to build on). The base that `revision_log`'s
+    /// spans are replayed over when restoring history on a future load.
+    history_base: Rope,
+    /// Every edit committed to `history` since `history_base`, as a `RevisionLogEntry` rather
+    /// than a `Transaction`, so it can be persisted without needing `helix_core` to support
+    /// serializing `Transaction`/`ChangeSet`. See `encode_revision_log`.
+    revision_log: Vec<RevisionLogEntry>,
+    /// Number of entries of `revision_log` currently committed (as opposed to undone). Our own
+    /// stand-in for `history`'s current revision, since `History` doesn't expose one.
+    revision: usize,
     /// Current document version, incremented at each change.
     version: i32, // should be usize?
 
+    /// Character encoding the file on disk is stored in. Detected from a BOM at load time,
+    /// falling back to `DEFAULT_ENCODING`, and re-used on save so round-tripping a non-UTF-8
+    /// file doesn't silently convert it.
+    encoding: &'static Encoding,
+    /// Whether the file on disk started with a byte-order-mark, so `save` can re-emit one
+    /// rather than silently dropping it.
+    had_bom: bool,
+    /// Line ending detected in the file on disk. The rope itself always uses `\n` internally;
+    /// this is re-applied when writing back out in `save`.
+    line_ending: LineEnding,
+    /// Fingerprint of the file on disk as of the last load or save, used to detect
+    /// modifications made by another process before we overwrite it.
+    disk_stamp: Option<DiskStamp>,
+    /// `revision` as of the last successful save or reload. `is_modified` compares this against
+    /// `revision`, so undoing back to the saved point is correctly reported as unmodified.
+    saved_revision: usize,
+    /// Directory persisted undo histories are read from and written to.
+    history_dir: PathBuf,
+    /// Set when a filesystem watcher (see `FileWatcher`) observes the file changing on disk.
+    /// Reconciled against local edit state by `reconcile_external_change`.
+    external_change_pending: bool,
+
     pub diagnostics: Vec<Diagnostic>,
     pub language_server: Option<Arc<helix_lsp::Client>>,
 }
@@ -64,6 +544,7 @@ impl Document {
     pub fn new(state: State) -> Self {
         let changes = ChangeSet::new(&state.doc);
         let old_state = None;
+        let history_base = state.doc.clone();
 
         Self {
             path: None,
@@ -74,9 +555,19 @@ impl Document {
             language: None,
             changes,
             old_state,
+            encoding: DEFAULT_ENCODING,
+            had_bom: false,
+            line_ending: LineEnding::default(),
+            disk_stamp: None,
+            saved_revision: 0,
+            history_dir: default_history_dir(),
+            external_change_pending: false,
             diagnostics: Vec::new(),
             version: 0,
             history: History::default(),
+            history_base,
+            revision_log: Vec::new(),
+            revision: 0,
             language_server: None,
         }
     }
@@ -84,50 +575,290 @@ impl Document {
     // TODO: passing scopes here is awkward
     // TODO: async fn?
     pub fn load(path: PathBuf, scopes: &[String]) -> Result<Self, Error> {
-        use std::{env, fs::File, io::BufReader};
+        use std::{env, fs::File, io::Read};
         let _current_dir = env::current_dir()?;
 
-        let doc = Rope::from_reader(BufReader::new(File::open(path.clone())?))?;
+        let mut file = File::open(path.clone())?;
+        let disk_stamp = DiskStamp::from_metadata(&file.metadata()?);
+
+        let mut raw = Vec::new();
+        file.read_to_end(&mut raw)?;
+        let hash = content_hash(&raw);
+
+        let (encoding, had_bom, text) = decode(&raw, DEFAULT_ENCODING);
+        let line_ending = detect_line_ending(&text);
+        let doc = Rope::from_str(&normalize_line_endings(text, line_ending));
 
         // TODO: create if not found
 
         let mut doc = Self::new(State::new(doc));
+        doc.encoding = encoding;
+        doc.had_bom = had_bom;
+        doc.line_ending = line_ending;
+        doc.disk_stamp = Some(disk_stamp);
 
         let language_config = LOADER.language_config_for_file_name(path.as_path());
         doc.set_language(language_config, scopes);
 
         // canonicalize path to absolute value
-        doc.path = Some(std::fs::canonicalize(path)?);
+        let path = std::fs::canonicalize(path)?;
+
+        // restore the undo tree from disk if it was recorded against exactly this content;
+        // a mismatched hash means the file was edited outside the editor since, so we discard
+        // the stale history rather than risk corrupt positions.
+        let history_file = history_path(&doc.history_dir, &path, hash);
+        if let Ok(data) = std::fs::read(&history_file) {
+            if let Some(payload) = decode_history_file(&data, hash) {
+                if let Some((base, log, cursor)) = decode_revision_log(payload) {
+                    // Replay the persisted edits forward over `base`, committing each one the
+                    // same way `append_changes_to_history` does live, to rebuild an equivalent
+                    // `History`. If the log doesn't land back on the content we just loaded,
+                    // it's stale or corrupt, so discard it rather than risk corrupt positions.
+                    let mut replayed_state = State::new(base.clone());
+                    let mut history = History::default();
+                    let mut replay_ok = true;
+
+                    for entry in log.iter().take(cursor) {
+                        if !replay_revision_log_entry(&mut replayed_state, &mut history, entry) {
+                            replay_ok = false;
+                            break;
+                        }
+                    }
+
+                    if replay_ok && replayed_state.doc == doc.state.doc {
+                        // `log` can still hold entries past `cursor` if the user had undone some
+                        // edits before saving; replay those too so `history`'s redo branch is
+                        // rebuilt, then undo back to `cursor` so `history`'s current position
+                        // (and thus `redo()`) agrees with `doc.revision`. Without this, those
+                        // entries would sit in `revision_log` with no matching committed
+                        // revision, and `redo()` would silently have nothing to return.
+                        for entry in log.iter().skip(cursor) {
+                            if !replay_revision_log_entry(&mut replayed_state, &mut history, entry)
+                            {
+                                replay_ok = false;
+                                break;
+                            }
+                        }
+
+                        if replay_ok {
+                            for _ in 0..log.len() - cursor {
+                                history.undo();
+                            }
+                            doc.history = history;
+                            doc.history_base = base;
+                            doc.revision_log = log;
+                            doc.revision = cursor;
+                            doc.saved_revision = cursor;
+                        }
+                    }
+                }
+            }
+        }
+
+        doc.path = Some(path);
 
         Ok(doc)
     }
 
+    /// Override the directory persisted undo histories are read from and written to.
+    pub fn set_history_dir(&mut self, dir: PathBuf) {
+        self.history_dir = dir;
+    }
+
     // TODO: do we need some way of ensuring two save operations on the same doc can't run at once?
     // or is that handled by the OS/async layer
-    pub fn save(&self) -> impl Future<Output = Result<(), anyhow::Error>> {
+    //
+    // Returns the new on-disk fingerprint on success; the caller applies it back onto the
+    // `Document` with `set_disk_stamp` and `mark_saved` once the future resolves, since the
+    // future only holds an owned snapshot of the text so that it doesn't block any further
+    // edits.
+    pub fn save(&self) -> impl Future<Output = Result<DiskStamp, SaveError>> {
         // we clone and move text + path into the future so that we asynchronously save the current
         // state without blocking any further edits.
 
         let text = self.text().clone();
         let path = self.path.clone().expect("Can't save with no path set!"); // TODO: handle no path
-
-        // TODO: mark changes up to now as saved
-        // TODO: mark dirty false
+        let encoding = self.encoding;
+        let had_bom = self.had_bom;
+        let line_ending = self.line_ending;
+        let last_known = self.disk_stamp;
+        let history_dir = self.history_dir.clone();
+        let history_payload =
+            encode_revision_log(&self.history_base, &self.revision_log, self.revision);
 
         async move {
-            use smol::{fs::File, prelude::*};
-            let mut file = File::create(path).await?;
+            use smol::{fs, prelude::*};
+            use std::hash::Hasher;
+
+            // Bail out before touching anything if the file changed on disk since we last
+            // loaded or saved it, rather than silently clobbering the external edit.
+            if let Some(last_known) = last_known {
+                if let Ok(metadata) = fs::metadata(&path).await {
+                    if DiskStamp::from_metadata(&metadata) != last_known {
+                        return Err(SaveError::ModifiedOnDisk);
+                    }
+                }
+            }
 
-            // write all the rope chunks to file
-            for chunk in text.chunks() {
-                file.write_all(chunk.as_bytes()).await?;
+            // Preserve the original file's permissions: `File::create` applies the process
+            // umask, and `rename` replaces the target's inode wholesale, so without this an
+            // executable script would lose its `+x` bit and a `chmod 600` secrets file would
+            // come back world-readable on every save.
+            let original_permissions = fs::metadata(&path).await.ok().map(|m| m.permissions());
+
+            // Write to a sibling temp file and fsync it, then rename over the target so a
+            // crash mid-write can never leave `path` truncated.
+            let tmp_path = tmp_path_for(&path);
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            {
+                let mut file = fs::File::create(&tmp_path).await?;
+
+                if let Some(permissions) = original_permissions {
+                    file.set_permissions(permissions).await?;
+                }
+
+                if had_bom {
+                    let bom = bom_bytes(encoding);
+                    hasher.write(bom);
+                    file.write_all(bom).await?;
+                }
+
+                // write all the rope chunks to file, re-encoding and re-emitting the original
+                // line ending so we don't silently rewrite e.g. a latin-1/CRLF file as UTF-8/LF.
+                for chunk in text.chunks() {
+                    let chunk: Cow<str> = if line_ending == LineEnding::Lf {
+                        Cow::Borrowed(chunk)
+                    } else {
+                        Cow::Owned(chunk.replace('\n', line_ending.as_str()))
+                    };
+                    let encoded = encode_str(encoding, &chunk);
+                    hasher.write(&encoded);
+                    file.write_all(&encoded).await?;
+                }
+                file.sync_all().await?;
             }
-            // TODO: flush?
+            fs::rename(&tmp_path, &path).await?;
+            let hash = hasher.finish();
+
+            // Persist the undo tree alongside the new content, best-effort: a failure here
+            // shouldn't fail the save itself, just cost us restored history next time.
+            let history_file = history_path(&history_dir, &path, hash);
+            if let Some(parent) = history_file.parent() {
+                let _ = fs::create_dir_all(parent).await;
+            }
+            let _ = fs::write(&history_file, encode_history_file(hash, &history_payload)).await;
 
-            Ok(())
+            Ok(DiskStamp::from_metadata(&fs::metadata(&path).await?))
         } // and_then notify save
     }
 
+    /// Record the on-disk fingerprint produced by a successful `save`, so the next save can
+    /// again detect modifications made by another process in the meantime.
+    pub fn set_disk_stamp(&mut self, stamp: DiskStamp) {
+        self.disk_stamp = Some(stamp);
+    }
+
+    /// Record that the current history revision has been saved, so `is_modified` reports
+    /// `false` until the buffer diverges from it again.
+    pub fn mark_saved(&mut self) {
+        self.saved_revision = self.revision;
+    }
+
+    /// Whether the document has unsaved changes: either edits not yet folded into the history
+    /// (see `append_changes_to_history`), or a history revision that doesn't match the one we
+    /// last saved or reloaded at. Undoing back to that revision reports `false` again.
+    pub fn is_modified(&self) -> bool {
+        !self.changes.is_empty() || self.revision != self.saved_revision
+    }
+
+    /// Re-read the file from disk and apply the difference as a single `Transaction`, rather
+    /// than discarding and recreating the document. Going through `apply`/`_apply` means the
+    /// undo tree gains an entry for the external edit, the tree-sitter `Syntax` is updated
+    /// incrementally, a `textDocument/didChange` notification fires, and the selection is
+    /// remapped through the change automatically.
+    pub fn reload(&mut self, scopes: &[String]) -> Result<(), Error> {
+        let path = self
+            .path
+            .clone()
+            .ok_or_else(|| Error::msg("Can't reload a document with no path set"))?;
+
+        let mut file = std::fs::File::open(&path)?;
+        let disk_stamp = DiskStamp::from_metadata(&file.metadata()?);
+
+        let mut raw = Vec::new();
+        use std::io::Read;
+        file.read_to_end(&mut raw)?;
+
+        let (encoding, had_bom, text) = decode(&raw, self.encoding);
+        let line_ending = detect_line_ending(&text);
+        let new_doc = Rope::from_str(&normalize_line_endings(text, line_ending));
+
+        let change = diff_as_change(self.text(), &new_doc);
+        let transaction = Transaction::change(&self.state, change.into_iter());
+
+        if !transaction.changes().is_empty() {
+            self.apply(&transaction);
+            self.append_changes_to_history();
+        }
+        // the buffer now matches the file on disk, so it's no longer modified
+        self.saved_revision = self.revision;
+
+        self.encoding = encoding;
+        self.had_bom = had_bom;
+        self.line_ending = line_ending;
+        self.disk_stamp = Some(disk_stamp);
+        self.external_change_pending = false;
+
+        // Only reclassify the language (and rebuild the syntax tree from scratch) if it
+        // actually changed; otherwise leave the incrementally-updated tree alone.
+        let language_config = LOADER.language_config_for_file_name(path.as_path());
+        if language_config.as_ref().map(|config| config.scope()) != self.language.as_deref() {
+            self.set_language(language_config, scopes);
+        }
+
+        Ok(())
+    }
+
+    /// Flag that a filesystem watcher (see `FileWatcher`) observed the file changing on disk.
+    /// Call `reconcile_external_change` to act on it.
+    ///
+    /// A no-op if the file's current `DiskStamp` still matches the one we last loaded or saved
+    /// with. `FileWatcher` watches the parent directory so it survives `save`'s own atomic
+    /// rename-over-`path`, so without this check, every one of our own saves would also report
+    /// as an external change - needlessly re-diffing a file we just wrote, or worse, getting
+    /// stuck pending if a new edit lands before anyone calls `reconcile_external_change`.
+    pub fn notify_external_change(&mut self) {
+        if let Some(path) = &self.path {
+            if let Ok(metadata) = std::fs::metadata(path) {
+                if Some(DiskStamp::from_metadata(&metadata)) == self.disk_stamp {
+                    return;
+                }
+            }
+        }
+        self.external_change_pending = true;
+    }
+
+    /// Whether an external modification has been flagged since the last load, save, or reload.
+    pub fn external_change_pending(&self) -> bool {
+        self.external_change_pending
+    }
+
+    /// Reconcile a pending external-change notification with local edit state: a clean buffer
+    /// is reloaded silently, while a dirty one is left alone (flag still set) so the caller can
+    /// prompt the user instead of clobbering their edits.
+    ///
+    /// Returns whether a reload happened.
+    pub fn reconcile_external_change(&mut self, scopes: &[String]) -> Result<bool, Error> {
+        if !self.external_change_pending {
+            return Ok(false);
+        }
+        if self.is_modified() {
+            return Ok(false);
+        }
+        self.reload(scopes)?;
+        Ok(true)
+    }
+
     pub fn set_language(
         &mut self,
         language_config: Option<Arc<helix_core::syntax::LanguageConfiguration>>,
@@ -184,7 +915,13 @@ impl Document {
                     .unwrap();
             }
 
-            // TODO: map state.diagnostics over changes::map_pos too
+            // remap diagnostics through the edit so stale LSP diagnostics keep tracking the
+            // right text as the user types between server publishes, rather than pointing at
+            // positions that have since shifted.
+            let changes = transaction.changes();
+            for diagnostic in &mut self.diagnostics {
+                diagnostic.range = remap_range(diagnostic.range.clone(), changes);
+            }
 
             // emit lsp notification
             if let Some(language_server) = &self.language_server {
@@ -222,6 +959,7 @@ impl Document {
     pub fn undo(&mut self) -> bool {
         if let Some(transaction) = self.history.undo() {
             self.version += 1;
+            self.revision = self.revision.saturating_sub(1);
             let success = self._apply(&transaction);
 
             // reset changeset to fix len
@@ -235,6 +973,7 @@ impl Document {
     pub fn redo(&mut self) -> bool {
         if let Some(transaction) = self.history.redo() {
             self.version += 1;
+            self.revision += 1;
 
             let success = self._apply(&transaction);
 
@@ -255,9 +994,10 @@ impl Document {
 
         let new_changeset = ChangeSet::new(self.text());
         let changes = std::mem::replace(&mut self.changes, new_changeset);
+        let selection = self.selection().clone();
         // Instead of doing this messy merge we could always commit, and based on transaction
         // annotations either add a new layer or compose into the previous one.
-        let transaction = Transaction::from(changes).with_selection(self.selection().clone());
+        let transaction = Transaction::from(changes).with_selection(selection.clone());
 
         // increment document version
         self.version += 1;
@@ -265,6 +1005,19 @@ impl Document {
         // HAXX: we need to reconstruct the state as it was before the changes..
         let old_state = self.old_state.take().expect("no old_state available");
 
+        // A commit after an undo discards whatever redo branch followed it; mirror that here so
+        // `revision_log` stays a straight line over `history_base` that replays back to exactly
+        // `self.state.doc`.
+        self.revision_log.truncate(self.revision);
+        if let Some(change) = diff_as_change(&old_state.doc, &self.state.doc) {
+            let (start, end, replacement) = change;
+            self.revision_log
+                .push((start, end, replacement.map(|t| t.to_string()), selection));
+        } else {
+            self.revision_log.push((0, 0, None, selection));
+        }
+        self.revision = self.revision_log.len();
+
         self.history.commit_revision(&transaction, &old_state);
     }
 
@@ -289,6 +1042,31 @@ impl Document {
         self.path.as_ref()
     }
 
+    #[inline]
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Force the encoding that `save` will re-encode this document's text into.
+    ///
+    /// `decode`/`load` only recognize UTF-16 via a BOM, falling back to `DEFAULT_ENCODING`
+    /// (UTF-8) otherwise, so forcing a conversion to it without emitting one would silently
+    /// corrupt the file on the very next load: the raw UTF-16 bytes would be misdecoded as
+    /// UTF-8 garbage. UTF-8 needs no such rescue, since that's already `decode`'s fallback, so
+    /// leave `had_bom` alone unless we're converting to one of the encodings that actually
+    /// depends on it to be recoverable.
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+            self.had_bom = true;
+        }
+        self.encoding = encoding;
+    }
+
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
     pub fn url(&self) -> Option<Url> {
         self.path().map(|path| Url::from_file_path(path).unwrap())
     }
@@ -326,10 +1104,96 @@ impl Document {
     }
 }
 
+/// An optional, single-file filesystem watcher. Internally watches the file's parent directory
+/// (see `FileWatcher::new` for why) and filters down to the one filename. Its notifications are
+/// meant to be forwarded into `Document::notify_external_change` so the editor can offer to
+/// reload; it does not touch the `Document` itself.
+pub struct FileWatcher {
+    rx: std::sync::mpsc::Receiver<()>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        use notify::{RecursiveMode, Watcher};
+
+        // Watch the parent directory rather than `path` itself: an atomic save (see
+        // `Document::save`) writes a sibling temp file and renames it over `path`, which
+        // replaces the inode a watch on the specific path is following. Such a watch goes dead
+        // after the very first save by anyone, silently defeating external-change detection for
+        // the rest of the session. A directory watch survives the rename; we filter its events
+        // down to the one filename we actually care about.
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let file_name = path.file_name().map(|name| name.to_owned());
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event {
+                    let matches = match &file_name {
+                        Some(file_name) => event
+                            .paths
+                            .iter()
+                            .any(|p| p.file_name() == Some(file_name.as_os_str())),
+                        None => true,
+                    };
+                    if matches {
+                        // the receiving end only cares that *something* changed; drop errors
+                        let _ = tx.send(());
+                    }
+                }
+            })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            rx,
+            _watcher: watcher,
+        })
+    }
+
+    /// Drain any filesystem events seen since the last poll, returning whether the watched
+    /// file changed.
+    pub fn poll(&self) -> bool {
+        let mut changed = false;
+        while self.rx.try_recv().is_ok() {
+            changed = true;
+        }
+        changed
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    /// A uniquely-named directory under the system temp dir, removed (recursively, best-effort)
+    /// when dropped - including on an early return from a failed `assert!` - so a panicking test
+    /// doesn't leave litter behind under `std::env::temp_dir()` on every failing CI run.
+    struct TempTestDir(PathBuf);
+
+    impl TempTestDir {
+        fn new(prefix: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("{prefix}-{:?}", std::thread::current().id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl std::ops::Deref for TempTestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempTestDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
     #[test]
     fn changeset_to_changes() {
         use helix_core::{Rope, State, Transaction};
@@ -430,4 +1294,395 @@ mod test {
             ]
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn content_hash_matches_chunked_incremental_hash() {
+        // `save` hashes the encoded output one chunk at a time via `Hasher::write`, while
+        // `load` hashes the whole file in one call to `content_hash`. The two must agree, or
+        // the history file `save` writes can never be found again by `load`.
+        let whole = content_hash(b"hello world, this is a test");
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        for chunk in ["hello world", ", this is", " a test"] {
+            std::hash::Hasher::write(&mut hasher, chunk.as_bytes());
+        }
+        let chunked = std::hash::Hasher::finish(&hasher);
+
+        assert_eq!(whole, chunked);
+    }
+
+    #[test]
+    fn decode_encode_round_trip_preserves_bom_and_utf16() {
+        // UTF-16LE with a BOM, as produced by e.g. Notepad. `decode` must report both the
+        // encoding and that a BOM was present, and re-encoding through `encode_str` (not the
+        // `Encoding::encode` convenience method, which maps UTF-16 to UTF-8) must round-trip
+        // back to the original bytes, BOM included.
+        let original = "hello\nworld".to_string();
+        let mut raw = vec![0xFF, 0xFE];
+        for unit in original.encode_utf16() {
+            raw.extend_from_slice(&unit.to_le_bytes());
+        }
+
+        let (encoding, had_bom, text) = decode(&raw, DEFAULT_ENCODING);
+        assert_eq!(encoding, encoding_rs::UTF_16LE);
+        assert!(had_bom);
+        assert_eq!(text, original);
+
+        let mut round_tripped = bom_bytes(encoding).to_vec();
+        round_tripped.extend(encode_str(encoding, &text));
+        assert_eq!(round_tripped, raw);
+    }
+
+    #[test]
+    fn set_encoding_forces_a_bom_for_utf16_so_the_conversion_stays_recoverable() {
+        // Without a BOM, `decode` has no way to recognize UTF-16 and falls back to
+        // `DEFAULT_ENCODING`, so forcing a BOM-less UTF-16LE/BE conversion would corrupt the
+        // file on the next load. `set_encoding` must force one on for those two encodings...
+        let mut doc = Document::new(State::new(Rope::from("hello world")));
+        assert!(!doc.had_bom);
+
+        doc.set_encoding(encoding_rs::UTF_16LE);
+        assert_eq!(doc.encoding(), encoding_rs::UTF_16LE);
+        assert!(doc.had_bom);
+
+        // ...but leave it alone when converting to an encoding that doesn't need one, like
+        // UTF-8, which `decode` already falls back to without a BOM.
+        let mut doc = Document::new(State::new(Rope::from("hello world")));
+        doc.set_encoding(encoding_rs::UTF_8);
+        assert!(!doc.had_bom);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn save_preserves_existing_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempTestDir::new("helix-save-permissions-test");
+        let path = dir.join("script.sh");
+        std::fs::write(&path, "#!/bin/sh\necho old\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+        let mut doc = Document::new(State::new(Rope::from("#!/bin/sh\necho new\n")));
+        doc.path = Some(path.clone());
+        doc.history_dir = dir.join("history");
+
+        smol::block_on(doc.save()).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o700);
+    }
+
+    #[test]
+    fn revision_log_round_trips_through_encode_decode() {
+        let base = Rope::from("hello world");
+        let log = vec![
+            (5, 5, Some(",".to_string()), Selection::single(6, 6)),
+            (0, 0, None, Selection::single(0, 0)),
+        ];
+
+        let encoded = encode_revision_log(&base, &log, 1);
+        let (decoded_base, decoded_log, cursor) = decode_revision_log(&encoded).unwrap();
+
+        assert_eq!(decoded_base, base);
+        assert_eq!(decoded_log, log);
+        assert_eq!(cursor, 1);
+    }
+
+    #[test]
+    fn history_survives_a_save_load_round_trip() {
+        // Reproduces the scenario the persisted-history feature exists for: make an edit,
+        // save, drop the in-memory `Document`, load the same file back, and undo should still
+        // be able to get back to the pre-edit text - without relying on `History` itself
+        // supporting serialization (it doesn't).
+        let dir = TempTestDir::new("helix-history-roundtrip-test");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "hello world").unwrap();
+
+        let mut doc = Document::load(path.clone(), &[]).unwrap();
+        doc.set_history_dir(dir.join("history"));
+
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((5, 5, Some(Tendril::from(",")))),
+        );
+        doc.apply(&transaction);
+        doc.append_changes_to_history();
+        assert_eq!(doc.text().to_string(), "hello, world");
+
+        let stamp = smol::block_on(doc.save()).unwrap();
+        doc.set_disk_stamp(stamp);
+        doc.mark_saved();
+
+        let mut reloaded = Document::load(path.clone(), &[]).unwrap();
+        reloaded.set_history_dir(dir.join("history"));
+        assert_eq!(reloaded.text().to_string(), "hello, world");
+        assert!(!reloaded.is_modified());
+
+        assert!(reloaded.undo());
+        assert_eq!(reloaded.text().to_string(), "hello world");
+    }
+
+    #[test]
+    fn selection_survives_a_save_load_round_trip_through_undo() {
+        // The persisted history log carries each edit's selection alongside its text span (see
+        // `RevisionLogEntry`), so undoing a revision rebuilt from disk restores the cursor the
+        // same way undoing a same-session revision does, not wherever `Document::load` happens
+        // to default the selection to.
+        let dir = TempTestDir::new("helix-selection-roundtrip-test");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "hello world").unwrap();
+
+        let mut doc = Document::load(path.clone(), &[]).unwrap();
+        doc.set_history_dir(dir.join("history"));
+
+        // First edit: insert "," after "hello", leaving the cursor right after the comma.
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((5, 5, Some(Tendril::from(",")))),
+        );
+        doc.apply(&transaction);
+        doc.set_selection(Selection::single(6, 6));
+        doc.append_changes_to_history();
+        assert_eq!(doc.text().to_string(), "hello, world");
+
+        // Second edit: append "!", leaving the cursor at the end of the file.
+        let end = doc.text().len_chars();
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((end, end, Some(Tendril::from("!")))),
+        );
+        doc.apply(&transaction);
+        doc.set_selection(Selection::single(end + 1, end + 1));
+        doc.append_changes_to_history();
+        assert_eq!(doc.text().to_string(), "hello, world!");
+
+        let stamp = smol::block_on(doc.save()).unwrap();
+        doc.set_disk_stamp(stamp);
+        doc.mark_saved();
+
+        let mut reloaded = Document::load(path.clone(), &[]).unwrap();
+        reloaded.set_history_dir(dir.join("history"));
+        assert_eq!(reloaded.text().to_string(), "hello, world!");
+
+        // Undoing the second edit should land the cursor where it was right before that edit
+        // was made - right after the comma - not at whatever selection `load` started with.
+        assert!(reloaded.undo());
+        assert_eq!(reloaded.text().to_string(), "hello, world");
+        assert_eq!(*reloaded.selection(), Selection::single(6, 6));
+    }
+
+    #[test]
+    fn redo_survives_a_save_load_round_trip_after_undoing_before_saving() {
+        // Make two edits, undo one (so `revision` trails `revision_log.len()`), then save in
+        // that state: the undone second edit is still in the persisted log past `cursor`, and
+        // `redo` should still be able to reach it after a reload, not just `undo`.
+        let dir = TempTestDir::new("helix-redo-roundtrip-test");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "hello world").unwrap();
+
+        let mut doc = Document::load(path.clone(), &[]).unwrap();
+        doc.set_history_dir(dir.join("history"));
+
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((5, 5, Some(Tendril::from(",")))),
+        );
+        doc.apply(&transaction);
+        doc.append_changes_to_history();
+        assert_eq!(doc.text().to_string(), "hello, world");
+
+        let end = doc.text().len_chars();
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((end, end, Some(Tendril::from("!")))),
+        );
+        doc.apply(&transaction);
+        doc.append_changes_to_history();
+        assert_eq!(doc.text().to_string(), "hello, world!");
+
+        assert!(doc.undo());
+        assert_eq!(doc.text().to_string(), "hello, world");
+
+        let stamp = smol::block_on(doc.save()).unwrap();
+        doc.set_disk_stamp(stamp);
+        doc.mark_saved();
+
+        let mut reloaded = Document::load(path.clone(), &[]).unwrap();
+        reloaded.set_history_dir(dir.join("history"));
+        assert_eq!(reloaded.text().to_string(), "hello, world");
+
+        assert!(reloaded.redo());
+        assert_eq!(reloaded.text().to_string(), "hello, world!");
+    }
+
+    #[test]
+    fn is_modified_is_false_again_after_undoing_back_to_the_saved_revision() {
+        let mut doc = Document::new(State::new(Rope::from("hello world")));
+        assert!(!doc.is_modified());
+
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((5, 5, Some(Tendril::from(",")))),
+        );
+        doc.apply(&transaction);
+        doc.append_changes_to_history();
+        assert_eq!(doc.text().to_string(), "hello, world");
+        assert!(doc.is_modified());
+
+        doc.mark_saved();
+        assert!(!doc.is_modified());
+
+        let transaction2 = Transaction::change(
+            &doc.state,
+            std::iter::once((0, 0, Some(Tendril::from("say ")))),
+        );
+        doc.apply(&transaction2);
+        doc.append_changes_to_history();
+        assert!(doc.is_modified());
+
+        assert!(doc.undo());
+        assert_eq!(doc.text().to_string(), "hello, world");
+        assert!(!doc.is_modified());
+    }
+
+    #[test]
+    fn file_watcher_survives_atomic_rename_over_the_watched_path() {
+        use std::time::{Duration, Instant};
+
+        let dir = TempTestDir::new("helix-watcher-rename-test");
+        let path = dir.join("watched.txt");
+        std::fs::write(&path, "old").unwrap();
+
+        let watcher = FileWatcher::new(&path).unwrap();
+        // Drain the create event from the write above.
+        std::thread::sleep(Duration::from_millis(100));
+        watcher.poll();
+
+        // Simulate Document::save's atomic write: write a sibling temp file, then rename it
+        // over `path`, replacing its inode the way a real save does.
+        let tmp_path = tmp_path_for(&path);
+        std::fs::write(&tmp_path, "new").unwrap();
+        std::fs::rename(&tmp_path, &path).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut seen = false;
+        while Instant::now() < deadline {
+            if watcher.poll() {
+                seen = true;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        assert!(
+            seen,
+            "watcher should still see changes after a rename-over-path save"
+        );
+    }
+
+    #[test]
+    fn notify_external_change_ignores_a_watcher_event_matching_our_own_save() {
+        // `FileWatcher` watches the parent directory (see `FileWatcher::new`) so it survives
+        // `save`'s own atomic rename-over-`path`, which means every one of our own saves also
+        // fires it. `notify_external_change` must recognize that the file's `DiskStamp` still
+        // matches the one `save` just produced, and not set the pending flag for it.
+        let dir = TempTestDir::new("helix-notify-own-save-test");
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "hello world").unwrap();
+
+        let mut doc = Document::load(path.clone(), &[]).unwrap();
+        doc.set_history_dir(dir.join("history"));
+
+        let stamp = smol::block_on(doc.save()).unwrap();
+        doc.set_disk_stamp(stamp);
+        doc.mark_saved();
+
+        // A watcher event fires, but the file on disk is exactly what we just wrote.
+        doc.notify_external_change();
+        assert!(!doc.external_change_pending());
+
+        // A genuine external edit, on the other hand, must still be reported.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&path, "hello world, edited elsewhere").unwrap();
+        doc.notify_external_change();
+        assert!(doc.external_change_pending());
+    }
+
+    /// Builds a `Diagnostic` covering `range`, with the rest of its fields set to innocuous
+    /// placeholders - only `range` is relevant to these tests.
+    fn test_diagnostic(range: std::ops::Range<usize>) -> Diagnostic {
+        Diagnostic {
+            range,
+            line: 0,
+            message: String::from("test diagnostic"),
+            severity: None,
+            code: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn inserting_at_a_diagnostics_start_excludes_the_insertion_from_its_range() {
+        // "hello [world] foo" - the diagnostic covers "world" (offsets 6..11).
+        let mut doc = Document::new(State::new(Rope::from("hello world foo")));
+        doc.diagnostics.push(test_diagnostic(6..11));
+
+        // Insert exactly at the diagnostic's start. The inserted text should land *before* the
+        // diagnostic, not be absorbed into it - so the range should shift forward by the
+        // insertion's length rather than just growing its start.
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((6, 6, Some(Tendril::from("XX")))),
+        );
+        doc.apply(&transaction);
+
+        assert_eq!(doc.text().to_string(), "hello XXworld foo");
+        assert_eq!(doc.diagnostics[0].range, 8..13);
+    }
+
+    #[test]
+    fn inserting_at_a_diagnostics_end_excludes_the_insertion_from_its_range() {
+        // "hello [world] foo" - the diagnostic covers "world" (offsets 6..11).
+        let mut doc = Document::new(State::new(Rope::from("hello world foo")));
+        doc.diagnostics.push(test_diagnostic(6..11));
+
+        // Insert exactly at the diagnostic's end. The inserted text should land *after* the
+        // diagnostic, not grow it - so the range should stay exactly where it was.
+        let transaction = Transaction::change(
+            &doc.state,
+            std::iter::once((11, 11, Some(Tendril::from("XX")))),
+        );
+        doc.apply(&transaction);
+
+        assert_eq!(doc.text().to_string(), "hello worldXX foo");
+        assert_eq!(doc.diagnostics[0].range, 6..11);
+    }
+
+    #[test]
+    fn deleting_up_to_a_diagnostics_start_shifts_its_range_back() {
+        // "hello [world] foo" - the diagnostic covers "world" (offsets 6..11).
+        let mut doc = Document::new(State::new(Rope::from("hello world foo")));
+        doc.diagnostics.push(test_diagnostic(6..11));
+
+        // Delete "hello " (0..6), which ends exactly at the diagnostic's start.
+        let transaction = Transaction::change(&doc.state, std::iter::once((0, 6, None)));
+        doc.apply(&transaction);
+
+        assert_eq!(doc.text().to_string(), "world foo");
+        assert_eq!(doc.diagnostics[0].range, 0..5);
+    }
+
+    #[test]
+    fn deleting_after_a_diagnostics_end_leaves_its_range_untouched() {
+        // "hello [world] foo" - the diagnostic covers "world" (offsets 6..11).
+        let mut doc = Document::new(State::new(Rope::from("hello world foo")));
+        doc.diagnostics.push(test_diagnostic(6..11));
+
+        // Delete " foo" (11..15), which starts exactly at the diagnostic's end.
+        let transaction = Transaction::change(&doc.state, std::iter::once((11, 15, None)));
+        doc.apply(&transaction);
+
+        assert_eq!(doc.text().to_string(), "hello world");
+        assert_eq!(doc.diagnostics[0].range, 6..11);
+    }
+}